@@ -0,0 +1,81 @@
+//! Config/theme file support: named profiles bundling the delimiter, skip
+//! pattern, size thresholds and field-color rules that would otherwise have
+//! to be repeated on every invocation, plus custom named colors.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Custom named colors, e.g. `brand = "rgb(200,30,80)"`.
+    #[serde(default)]
+    pub colors: HashMap<String, String>,
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Profile {
+    pub delimeter: Option<String>,
+    pub skip: Option<String>,
+    #[cfg(feature = "size-color")]
+    pub yellow_size: Option<String>,
+    #[cfg(feature = "size-color")]
+    pub red_size: Option<String>,
+    /// `FIELD:COLOR` rules, in the same syntax as `-f`.
+    #[serde(default)]
+    pub fields: Vec<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+}
+
+/// The default config path: `$XDG_CONFIG_HOME/hl/config.toml`, falling back
+/// to `$HOME/.config/hl/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("hl").join("config.toml"))
+}
+
+pub fn load(path: &std::path::Path) -> Result<Config, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Pick `--config PATH` out of the raw argv before the real CLI parser runs,
+/// since the config's custom colors must be installed before `options().run()`
+/// parses any `-f field:color` argument that might reference them.
+pub fn prescan_config_path(args: &[String]) -> Option<PathBuf> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(value) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(value));
+        }
+        if arg == "--config" {
+            return iter.next().map(PathBuf::from);
+        }
+    }
+    None
+}
@@ -37,68 +37,236 @@
 //! cpu family  : (6)
 //! ```
 
+mod config;
+
 use bpaf::Bpaf;
 #[cfg(feature = "size-color")]
 use bytesize::ByteSize;
 use core::fmt;
-use std::fmt::{Display, Formatter, Write as _};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::IsTerminal;
 use std::io::Write as _;
 use std::num::ParseIntError;
+use std::path::PathBuf;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Bpaf)]
+#[derive(Debug, Clone)]
 pub enum Color {
-    Ansi(String),
+    Ansi(AnsiColor),
     #[cfg(feature = "size-color")]
     Size
 }
 
-impl Display for Color {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Color::Ansi(ansi) => f.write_str(ansi),
-            Color::Size => Err(fmt::Error),
+/// A parsed foreground color, kept unresolved (rather than formatted straight
+/// to an escape sequence) so it can be downsampled to the terminal's real
+/// color depth before it is rendered.
+#[derive(Debug, Clone, Copy)]
+pub enum AnsiColor {
+    /// One of the 8 basic colors, or 9 for the default color.
+    Basic(u8),
+    /// A 256-color palette index.
+    Fixed(u8),
+    /// A 24-bit truecolor value.
+    Rgb(u8, u8, u8),
+}
+
+/// The xterm 6x6x6 color cube's levels for each channel.
+const CUBE_LEVELS: [u16; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Canonical RGB values for the 16 standard ANSI colors, in `3x`/`9x` order.
+const ANSI_16_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+fn sq_dist(r1: u8, g1: u8, b1: u8, r2: u16, g2: u16, b2: u16) -> i32 {
+    let dr = r1 as i32 - r2 as i32;
+    let dg = g1 as i32 - g2 as i32;
+    let db = b1 as i32 - b2 as i32;
+    dr * dr + dg * dg + db * db
+}
+
+/// Map a truecolor RGB value to the nearest 256-color palette index, picking
+/// whichever of the 6x6x6 color cube or the 24-entry grayscale ramp is closer.
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    let nearest_level = |c: u8| {
+        CUBE_LEVELS
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &level)| (level as i32 - c as i32).abs())
+            .map(|(i, _)| i)
+            .unwrap()
+    };
+    let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+    let cube_dist = sq_dist(r, g, b, CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+
+    let avg = (r as i32 + g as i32 + b as i32) / 3;
+    let gray_n = (((avg - 8) as f64 / 10.0).round().clamp(0.0, 23.0)) as u16;
+    let gray_value = 8 + 10 * gray_n;
+    let gray_index = 232 + gray_n;
+    let gray_dist = sq_dist(r, g, b, gray_value, gray_value, gray_value);
+
+    if gray_dist < cube_dist {
+        gray_index as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+/// Map a 256-color palette index back to its canonical RGB value.
+fn fixed_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI_16_RGB[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            let (r, g, b) = (n / 36, (n % 36) / 6, n % 6);
+            (
+                CUBE_LEVELS[r as usize] as u8,
+                CUBE_LEVELS[g as usize] as u8,
+                CUBE_LEVELS[b as usize] as u8,
+            )
+        }
+        232..=255 => {
+            let gray = (8 + 10 * (n - 232) as u16) as u8;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Match an RGB value against the 16 standard ANSI colors by nearest squared
+/// distance and render it in the `3x`/`9x` form.
+fn nearest_basic_escape(r: u8, g: u8, b: u8) -> String {
+    let (idx, _) = ANSI_16_RGB
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, &(cr, cg, cb))| sq_dist(r, g, b, cr as u16, cg as u16, cb as u16))
+        .unwrap();
+    if idx < 8 {
+        format!("\x1B[3{}m", idx)
+    } else {
+        format!("\x1B[9{}m", idx - 8)
+    }
+}
+
+/// Interpolate green->yellow->red on a log scale between `floor` and `ceil`,
+/// for `--size-gradient`. `size`, `floor` and `ceil` are byte counts.
+#[cfg(feature = "size-color")]
+fn gradient_color(size: u64, floor: u64, ceil: u64) -> (u8, u8, u8) {
+    let lerp = |a: u8, b: u8, t: f64| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+
+    let t = if ceil <= floor {
+        1.0
+    } else {
+        let (size, floor, ceil) = (size.max(1) as f64, floor.max(1) as f64, ceil as f64);
+        ((size.ln() - floor.ln()) / (ceil.ln() - floor.ln())).clamp(0.0, 1.0)
+    };
+
+    let (green, yellow, red) = (ANSI_16_RGB[2], ANSI_16_RGB[3], ANSI_16_RGB[1]);
+    let (from, to, local_t) = if t <= 0.5 {
+        (green, yellow, t / 0.5)
+    } else {
+        (yellow, red, (t - 0.5) / 0.5)
+    };
+    (
+        lerp(from.0, to.0, local_t),
+        lerp(from.1, to.1, local_t),
+        lerp(from.2, to.2, local_t),
+    )
+}
+
+impl AnsiColor {
+    /// Downsample this color to `depth` and render the escape sequence.
+    fn render(&self, depth: ColorDepth) -> String {
+        match (self, depth) {
+            (AnsiColor::Basic(n), _) => format!("\x1B[3{}m", n),
+            (AnsiColor::Fixed(n), ColorDepth::TrueColor | ColorDepth::Ansi256) => {
+                format!("\x1B[38;5;{}m", n)
+            }
+            (AnsiColor::Fixed(n), ColorDepth::Ansi16) => {
+                let (r, g, b) = fixed_to_rgb(*n);
+                nearest_basic_escape(r, g, b)
+            }
+            (AnsiColor::Rgb(r, g, b), ColorDepth::TrueColor) => {
+                format!("\x1B[38;2;{};{};{}m", r, g, b)
+            }
+            (AnsiColor::Rgb(r, g, b), ColorDepth::Ansi256) => {
+                format!("\x1B[38;5;{}m", rgb_to_256(*r, *g, *b))
+            }
+            (AnsiColor::Rgb(r, g, b), ColorDepth::Ansi16) => nearest_basic_escape(*r, *g, *b),
         }
     }
 }
+
 impl FromStr for Color {
     type Err = ParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        //let mut f = Cursor::new(String::new());
-        let mut f = String::new();
-        f.write_str("\x1B[3")?; // change 3 to 4 for background and add 6 for bright colors
-        match input {
-            "default" => write!(f, "9")?,
-            "black" => write!(f, "0")?,
-            "red" => write!(f, "1")?,
-            "green" => write!(f, "2")?,
-            "yellow" => write!(f, "3")?,
-            "blue" => write!(f, "4")?,
-            "magenta" => write!(f, "5")?,
-            "cyan" => write!(f, "6")?,
-            "white" => write!(f, "7")?,
-            input if input.starts_with("fixed(") && input.ends_with(')') => {
-                let in_par = input.strip_prefix("fixed(").unwrap().strip_suffix(')').unwrap();
-                let num: usize = in_par.parse()?;
-                write!(f, "8;5;{}", num)?;
-            },
-            input if input.starts_with("rgb(") && input.matches(',').count() == 2 && input.ends_with(')') => {
-                let in_par = input.strip_prefix("rgb(").unwrap().strip_suffix(')').unwrap();
-                use itertools::Itertools;
-                let (red, green, blue) = in_par.splitn(3, ',').collect_tuple().unwrap();
-                let (red, green, blue): (usize, usize, usize) = (red.parse()?, green.parse()?, blue.parse()?);
-                write!(f, "8;2;{};{};{}", red, green, blue)?;
-            },
-            #[cfg(feature = "size-color")]
-            "size" => return Ok(Color::Size),
-            input => return Err(ParseError::UnknownColor(input.to_owned())),
+        let basic = match input {
+            "default" => Some(9),
+            "black" => Some(0),
+            "red" => Some(1),
+            "green" => Some(2),
+            "yellow" => Some(3),
+            "blue" => Some(4),
+            "magenta" => Some(5),
+            "cyan" => Some(6),
+            "white" => Some(7),
+            _ => None,
         };
-        f.write_str("m")?;
-        Ok(Color::Ansi(f))
+        if let Some(n) = basic {
+            return Ok(Color::Ansi(AnsiColor::Basic(n)));
+        }
+        if let Some(in_par) = input.strip_prefix("fixed(").and_then(|s| s.strip_suffix(')')) {
+            let num: u8 = in_par.parse()?;
+            return Ok(Color::Ansi(AnsiColor::Fixed(num)));
+        }
+        if input.starts_with("rgb(") && input.matches(',').count() == 2 && input.ends_with(')') {
+            let in_par = input.strip_prefix("rgb(").unwrap().strip_suffix(')').unwrap();
+            use itertools::Itertools;
+            let (red, green, blue) = in_par.splitn(3, ',').collect_tuple().unwrap();
+            let (red, green, blue): (u8, u8, u8) = (red.parse()?, green.parse()?, blue.parse()?);
+            return Ok(Color::Ansi(AnsiColor::Rgb(red, green, blue)));
+        }
+        #[cfg(feature = "size-color")]
+        if input == "size" {
+            return Ok(Color::Size);
+        }
+        if let Some(spec) = CUSTOM_COLORS.with(|colors| colors.borrow().get(input).cloned()) {
+            return spec.parse();
+        }
+        Err(ParseError::UnknownColor(input.to_owned()))
     }
 }
 
+thread_local! {
+    /// Named colors from the config file's `[colors]` table, consulted by
+    /// `Color::from_str` before it gives up with `UnknownColor`.
+    static CUSTOM_COLORS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Install the config file's custom named colors. Must run before any
+/// `Color`/`FieldColor` value is parsed, since those may reference them.
+fn install_custom_colors(colors: HashMap<String, String>) {
+    CUSTOM_COLORS.with(|c| *c.borrow_mut() = colors);
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ParseError {
     #[error("unknown color {0}")]
@@ -111,13 +279,131 @@ pub enum ParseError {
     AnsiFmtError(#[from] fmt::Error),
     #[error(transparent)]
     Anyhow(#[from] anyhow::Error),
+    #[error("unknown value for --color: {0} (expected auto, always or never)")]
+    UnknownWhen(String),
+    #[error("unknown value for --color-depth: {0} (expected truecolor, 256 or 16)")]
+    UnknownColorDepth(String),
     #[error("unknown error")]
     Unknown,
 }
 
+/// When to emit ANSI color escapes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Display for When {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            When::Auto => "auto",
+            When::Always => "always",
+            When::Never => "never",
+        })
+    }
+}
+
+impl FromStr for When {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "auto" => Ok(When::Auto),
+            "always" => Ok(When::Always),
+            "never" => Ok(When::Never),
+            input => Err(ParseError::UnknownWhen(input.to_owned())),
+        }
+    }
+}
+
+/// Whether the current terminal advertises support for ANSI color, à la the
+/// `supports-color` heuristic: any `COLORTERM` value means color; otherwise
+/// no color for `TERM=dumb` or an unset `TERM`.
+fn terminal_supports_color() -> bool {
+    if std::env::var_os("COLORTERM").is_some() {
+        return true;
+    }
+    match std::env::var("TERM") {
+        Ok(term) => term != "dumb",
+        Err(_) => false,
+    }
+}
+
+/// The number of colors the target terminal can actually display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl Display for ColorDepth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorDepth::TrueColor => "truecolor",
+            ColorDepth::Ansi256 => "256",
+            ColorDepth::Ansi16 => "16",
+        })
+    }
+}
+
+impl FromStr for ColorDepth {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "truecolor" => Ok(ColorDepth::TrueColor),
+            "256" => Ok(ColorDepth::Ansi256),
+            "16" => Ok(ColorDepth::Ansi16),
+            input => Err(ParseError::UnknownColorDepth(input.to_owned())),
+        }
+    }
+}
+
+/// Detect the terminal's color depth from `$COLORTERM` and `$TERM`.
+fn detect_color_depth() -> ColorDepth {
+    if let Some(colorterm) = std::env::var_os("COLORTERM") {
+        let colorterm = colorterm.to_string_lossy();
+        if colorterm.contains("truecolor") || colorterm.contains("24bit") {
+            return ColorDepth::TrueColor;
+        }
+    }
+    if std::env::var("TERM").is_ok_and(|term| term.contains("256color")) {
+        return ColorDepth::Ansi256;
+    }
+    ColorDepth::Ansi16
+}
+
+/// Resolve the effective color setting from `--color`, `--plain` and `NO_COLOR`.
+fn use_color(when: When, plain: bool) -> bool {
+    if plain {
+        return false;
+    }
+    match when {
+        When::Always => true,
+        When::Never => false,
+        When::Auto => {
+            std::env::var_os("NO_COLOR").is_none()
+                && std::io::stdout().is_terminal()
+                && terminal_supports_color()
+        }
+    }
+}
+
+/// Which field a `FieldColor` applies to: a (possibly negative) position, as
+/// produced by delimiter splitting or counting regex matches, or the name of
+/// a `--regex` capture group.
+#[derive(Debug, Clone)]
+enum FieldRef {
+    Index(isize),
+    Name(String),
+}
+
 #[derive(Debug, Clone)]
 struct FieldColor {
-    field: isize,
+    field: FieldRef,
     color: Color,
 }
 impl FromStr for FieldColor {
@@ -125,33 +411,170 @@ impl FromStr for FieldColor {
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         let (field, color) = input.split_once(':').ok_or(ParseError::MissingColonField)?;
+        let field = match field.parse::<isize>() {
+            Ok(index) => FieldRef::Index(index),
+            Err(_) => FieldRef::Name(field.to_owned()),
+        };
         Ok(Self {
-            field: field.parse()?,
+            field,
             color: color.parse()?,
         })
     }
 }
 
+/// Resolve `fields`' index-based rules against the `i`-th of `total` fields,
+/// counting from the end for negative indices.
+fn find_indexed_field(fields: &[FieldColor], i: usize, total: usize) -> Option<&FieldColor> {
+    fields.iter().find(|fc| match fc.field {
+        FieldRef::Index(field) => {
+            let field = if field < 0 { total as isize + field } else { field };
+            field >= 0 && field as usize == i
+        }
+        FieldRef::Name(_) => false,
+    })
+}
+
+/// Select the spans of the named capture groups referenced by `-f NAME:COLOR`
+/// rules, left to right with a deterministic tiebreak for groups that share a
+/// start (so output doesn't depend on `name_colors`' hash-map iteration
+/// order). Errors if two selected groups overlap, e.g. a nested sub-group
+/// like `(?P<timestamp>...(?P<time>...))` with both names given a color --
+/// painting those would require drawing one color span inside another.
+fn select_named_groups<'a>(
+    captures: &regex::Captures,
+    name_colors: &HashMap<&str, &'a Color>,
+) -> Result<Vec<(usize, usize, &'a Color)>, anyhow::Error> {
+    let mut groups: Vec<_> = name_colors
+        .iter()
+        .filter_map(|(name, color)| captures.name(name).map(|g| (g.start(), g.end(), *name, *color)))
+        .collect();
+    groups.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.2.cmp(b.2)));
+
+    let mut selected = Vec::with_capacity(groups.len());
+    let mut cursor = 0;
+    for (start, end, name, color) in groups {
+        if start < cursor {
+            return Err(anyhow::anyhow!(
+                "named capture group `{}` overlaps with another selected group; \
+                 -f can't color nested or overlapping named groups",
+                name
+            ));
+        }
+        cursor = end;
+        selected.push((start, end, color));
+    }
+    Ok(selected)
+}
+
+/// Renders a matched field's `Color` to `stdout`, holding the precomputed
+/// basic-color escapes and size thresholds shared by every field on a line.
+struct Painter {
+    use_color: bool,
+    depth: ColorDepth,
+    default_color: String,
+    #[cfg(feature = "size-color")]
+    green_color: String,
+    #[cfg(feature = "size-color")]
+    yellow_color: String,
+    #[cfg(feature = "size-color")]
+    red_color: String,
+    #[cfg(feature = "size-color")]
+    size_gradient: bool,
+    #[cfg(feature = "size-color")]
+    size_gradient_floor: ByteSize,
+    #[cfg(feature = "size-color")]
+    yellow_size: ByteSize,
+    #[cfg(feature = "size-color")]
+    red_size: ByteSize,
+}
+
+impl Painter {
+    fn paint(
+        &self,
+        stdout: &mut impl std::io::Write,
+        text: &str,
+        color: &Color,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.use_color {
+            stdout.write_all(text.as_bytes())?;
+            return Ok(());
+        }
+        match color {
+            Color::Ansi(ansi) => {
+                write!(stdout, "{}{}{}", ansi.render(self.depth), text, self.default_color)?;
+            }
+            #[cfg(feature = "size-color")]
+            Color::Size => {
+                let size: ByteSize = text.trim().parse()?;
+                let ansi = if self.size_gradient {
+                    let (r, g, b) = gradient_color(
+                        size.as_u64(),
+                        self.size_gradient_floor.as_u64(),
+                        self.red_size.as_u64(),
+                    );
+                    AnsiColor::Rgb(r, g, b).render(self.depth)
+                } else if size > self.red_size {
+                    self.red_color.clone()
+                } else if size > self.yellow_size {
+                    self.yellow_color.clone()
+                } else {
+                    self.green_color.clone()
+                };
+                write!(stdout, "{}{}{}", ansi, text, self.default_color)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Bpaf)]
 #[bpaf(options)]
 pub struct Options {
     #[bpaf(short, long("field"), argument("FIELD:COLOR"))]
-    /// Color fields
+    /// Color fields (overrides the selected profile's fields)
     fields: Vec<FieldColor>,
-    #[bpaf(short, long, fallback(" ".to_owned()), debug_fallback)]
-    /// Custom delimeter for fields
-    delimeter: String,
+    #[bpaf(short, long, argument("DELIM"))]
+    /// Custom delimeter for fields (overrides the selected profile, defaults to " "; mutually exclusive with --regex)
+    delimeter: Option<String>,
+    #[bpaf(long, argument("PATTERN"))]
+    /// Use a regex pattern's matches as the fields instead of splitting on a delimiter
+    /// (text between matches is printed uncolored); `-f` may then
+    /// name a capture group (e.g. `-fmsg:red` for `(?<msg>...)`), mutually exclusive with --delimeter
+    regex: Option<String>,
     #[bpaf(short, long)]
-    /// Skip to a substring and match fields after it
+    /// Skip to a substring and match fields after it (overrides the selected profile)
     skip: Option<String>,
+    #[bpaf(long, argument("PATH"))]
+    /// Path to a config file (defaults to $XDG_CONFIG_HOME/hl/config.toml)
+    config: Option<PathBuf>,
+    #[bpaf(long, argument("NAME"))]
+    /// Named profile from the config file to use for defaults
+    profile: Option<String>,
+    #[bpaf(long, fallback(When::Auto), display_fallback)]
+    /// When to use color: auto, always, or never
+    color: When,
+    #[bpaf(long)]
+    /// Shortcut for --color=never
+    plain: bool,
+    #[bpaf(long("color-depth"), fallback(detect_color_depth()), display_fallback)]
+    /// Color depth of the terminal: truecolor, 256, or 16
+    color_depth: ColorDepth,
     #[cfg(feature = "size-color")]
-    #[bpaf(long, fallback(ByteSize::mb(20)), display_fallback)]
-    /// For the "size" color
-    yellow_size: ByteSize,
+    #[bpaf(long, argument("SIZE"))]
+    /// For the "size" color (overrides the selected profile, defaults to 20MB)
+    yellow_size: Option<ByteSize>,
     #[cfg(feature = "size-color")]
-    #[bpaf(long, fallback(ByteSize::mb(100)), display_fallback)]
-    /// For the "size" color
-    red_size: ByteSize,
+    #[bpaf(long, argument("SIZE"))]
+    /// For the "size" color (overrides the selected profile, defaults to 100MB)
+    red_size: Option<ByteSize>,
+    #[cfg(feature = "size-color")]
+    #[bpaf(long)]
+    /// Interpolate the "size" color continuously instead of three fixed buckets
+    size_gradient: bool,
+    #[cfg(feature = "size-color")]
+    #[bpaf(long, fallback(ByteSize::kb(1)), display_fallback)]
+    /// Lower bound of the --size-gradient log scale
+    size_gradient_floor: ByteSize,
 }
 
 //fn main() -> Result<(), anyhow::Error> {
@@ -159,12 +582,153 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(unix)]
     reset_sigpipe();
     //Err(anyhow::anyhow!("foo").context("bar"))?;
+
+    // The config's custom colors must be installed before bpaf parses any
+    // `-f field:color` argument that might reference them, so pick
+    // `--config PATH` out of argv ourselves ahead of the real parse.
+    let args: Vec<String> = std::env::args().collect();
+    let explicit_config_path = config::prescan_config_path(&args);
+    let early_config_path = explicit_config_path.clone().or_else(config::default_path);
+    // Only the XDG-derived default path may silently not exist; a path the
+    // user actually typed on the command line has to resolve or error.
+    let load_config = |path: &Option<PathBuf>, explicit: bool| match path {
+        Some(path) if path.exists() => config::load(path).map_err(anyhow::Error::from),
+        Some(path) if explicit => {
+            Err(anyhow::anyhow!("config file {} does not exist", path.display()))
+        }
+        _ => Ok(config::Config::default()),
+    };
+    install_custom_colors(load_config(&early_config_path, explicit_config_path.is_some())?.colors);
+
     let options = options().run();
+    let use_color = use_color(options.color, options.plain);
+    let depth = options.color_depth;
 
-    let default_color = Color::from_str("default")?;
-    let green_color = Color::from_str("green")?;
-    let yellow_color = Color::from_str("yellow")?;
-    let red_color = Color::from_str("red")?;
+    // Re-resolve using the now-authoritative `--config` from bpaf. This should
+    // always agree with our hand-rolled argv prescan above; if it doesn't,
+    // the prescan already installed colors from the wrong file, so bail out
+    // instead of silently rendering with a mismatched custom-color set.
+    let config_explicit = options.config.is_some();
+    let config_path = options.config.clone().or_else(|| early_config_path.clone());
+    if config_path != early_config_path {
+        Err(anyhow::anyhow!(
+            "--config resolved to a different path during argument parsing ({:?}) than during \
+             the early scan ({:?}); this is a bug in config path resolution",
+            config_path,
+            early_config_path
+        ))?;
+    }
+    let config = load_config(&config_path, config_explicit)?;
+    // Re-install in case the authoritative load's colors differ from the
+    // prescan's (e.g. the file changed between the two reads).
+    install_custom_colors(config.colors.clone());
+    let profile = match &options.profile {
+        Some(name) => Some(
+            config
+                .profile
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("no profile named `{}` in config file", name))?,
+        ),
+        None => None,
+    };
+
+    let delimeter_explicit = options.delimeter.is_some();
+    let profile_delimeter = profile.and_then(|p| p.delimeter.clone());
+    let delimeter = options
+        .delimeter
+        .or_else(|| profile_delimeter.clone())
+        .unwrap_or_else(|| " ".to_owned());
+    let skip = options.skip.or_else(|| profile.and_then(|p| p.skip.clone()));
+    let fields = if !options.fields.is_empty() {
+        options.fields
+    } else if let Some(profile) = profile {
+        profile
+            .fields
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<Result<_, ParseError>>()?
+    } else {
+        Vec::new()
+    };
+    #[cfg(feature = "size-color")]
+    let yellow_size = match options.yellow_size {
+        Some(size) => size,
+        None => match profile.and_then(|p| p.yellow_size.as_deref()) {
+            Some(s) => s
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid yellow_size in profile: {}", e))?,
+            None => ByteSize::mb(20),
+        },
+    };
+    #[cfg(feature = "size-color")]
+    let red_size = match options.red_size {
+        Some(size) => size,
+        None => match profile.and_then(|p| p.red_size.as_deref()) {
+            Some(s) => s
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid red_size in profile: {}", e))?,
+            None => ByteSize::mb(100),
+        },
+    };
+
+    if options.regex.is_some() && delimeter_explicit {
+        Err(anyhow::anyhow!("--regex and --delimeter are mutually exclusive"))?;
+    }
+    if options.regex.is_some() && profile_delimeter.is_some() {
+        Err(anyhow::anyhow!(
+            "--regex is mutually exclusive with the selected profile's delimeter"
+        ))?;
+    }
+    let regex = options.regex.as_deref().map(regex::Regex::new).transpose()?;
+    match &regex {
+        Some(re) => {
+            for fc in &fields {
+                let FieldRef::Name(name) = &fc.field else {
+                    continue;
+                };
+                if re.capture_names().flatten().all(|n| n != name) {
+                    Err(anyhow::anyhow!("--regex has no capture group named `{}`", name))?;
+                }
+            }
+        }
+        None => {
+            for fc in &fields {
+                if let FieldRef::Name(name) = &fc.field {
+                    Err(anyhow::anyhow!(
+                        "-f `{}` is not a valid field index (named fields require --regex)",
+                        name
+                    ))?;
+                }
+            }
+        }
+    }
+    let name_colors: HashMap<&str, &Color> = fields
+        .iter()
+        .filter_map(|fc| match &fc.field {
+            FieldRef::Name(name) => Some((name.as_str(), &fc.color)),
+            FieldRef::Index(_) => None,
+        })
+        .collect();
+
+    let painter = Painter {
+        use_color,
+        depth,
+        default_color: AnsiColor::Basic(9).render(depth),
+        #[cfg(feature = "size-color")]
+        green_color: AnsiColor::Basic(2).render(depth),
+        #[cfg(feature = "size-color")]
+        yellow_color: AnsiColor::Basic(3).render(depth),
+        #[cfg(feature = "size-color")]
+        red_color: AnsiColor::Basic(1).render(depth),
+        #[cfg(feature = "size-color")]
+        size_gradient: options.size_gradient,
+        #[cfg(feature = "size-color")]
+        size_gradient_floor: options.size_gradient_floor,
+        #[cfg(feature = "size-color")]
+        yellow_size,
+        #[cfg(feature = "size-color")]
+        red_size,
+    };
 
     let stdin = std::io::stdin();
     let mut stdout = std::io::stdout();
@@ -177,7 +741,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
 
         // Implement skip
-        let buf = if let Some(ref pat) = options.skip {
+        let buf = if let Some(ref pat) = skip {
             let (left, right) = buf.split_once(pat).ok_or_else(||anyhow::anyhow!("skip not found"))?;
             stdout.write_all(left.as_bytes())?;
             stdout.write_all(pat.as_bytes())?;
@@ -186,29 +750,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             buf
         };
 
-        let split: Vec<_> = buf.split_inclusive(&options.delimeter).collect();
-        // TODO: negative indexes for fields
-        for (i, text) in split.iter().enumerate() {
-            if let Some(fieldcolor) = options.fields.iter().find(|fc| fc.field >= 0 && fc.field as usize == i) {
-                match &fieldcolor.color {
-                    Color::Ansi(ansi) => {
-                        write!(stdout, "{}{}{}", ansi, text, default_color)?;
-                    },
-                    #[cfg(feature = "size-color")]
-                    Color::Size => {
-                        let size: ByteSize = text.trim().parse()?;
-                        let color = if size > options.red_size {
-                            &red_color
-                        } else if size > options.yellow_size {
-                            &yellow_color
-                        } else {
-                            &green_color
-                        };
-                        write!(stdout, "{}{}{}", color, text, default_color)?;
+        if let Some(re) = &regex {
+            let matches: Vec<_> = re.captures_iter(&buf).collect();
+            let mut cursor = 0;
+            for (i, captures) in matches.iter().enumerate() {
+                let whole = captures.get(0).unwrap();
+                stdout.write_all(&buf.as_bytes()[cursor..whole.start()])?;
+
+                if let Some(fieldcolor) = find_indexed_field(&fields, i, matches.len()) {
+                    painter.paint(&mut stdout, whole.as_str(), &fieldcolor.color)?;
+                } else {
+                    let groups = select_named_groups(captures, &name_colors)?;
+
+                    let mut group_cursor = whole.start();
+                    for (start, end, color) in groups {
+                        stdout.write_all(&buf.as_bytes()[group_cursor..start])?;
+                        painter.paint(&mut stdout, &buf[start..end], color)?;
+                        group_cursor = end;
                     }
+                    stdout.write_all(&buf.as_bytes()[group_cursor..whole.end()])?;
+                }
+
+                cursor = whole.end();
+            }
+            stdout.write_all(&buf.as_bytes()[cursor..])?;
+        } else {
+            let split: Vec<_> = buf.split_inclusive(&delimeter).collect();
+            for (i, text) in split.iter().enumerate() {
+                match find_indexed_field(&fields, i, split.len()) {
+                    Some(fieldcolor) => painter.paint(&mut stdout, text, &fieldcolor.color)?,
+                    None => stdout.write_all(text.as_bytes())?,
                 }
-            } else {
-                stdout.write_all(text.as_bytes())?;
             }
         }
     }
@@ -220,3 +792,100 @@ fn reset_sigpipe() {
         libc::signal(libc::SIGPIPE, libc::SIG_DFL);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_to_256_picks_the_cube_entry_for_pure_red() {
+        assert_eq!(rgb_to_256(255, 0, 0), 196);
+    }
+
+    #[test]
+    fn fixed_to_rgb_round_trips_the_cube_entry_for_pure_red() {
+        assert_eq!(fixed_to_rgb(196), (255, 0, 0));
+    }
+
+    #[test]
+    fn rgb_to_256_picks_the_gray_ramp_for_mid_gray() {
+        assert_eq!(rgb_to_256(128, 128, 128), 244);
+    }
+
+    #[test]
+    fn fixed_to_rgb_round_trips_a_basic_ansi_index() {
+        assert_eq!(fixed_to_rgb(9), (255, 0, 0));
+    }
+
+    #[test]
+    fn nearest_basic_escape_matches_pure_red() {
+        assert_eq!(nearest_basic_escape(255, 0, 0), "\x1B[91m");
+    }
+
+    #[test]
+    fn nearest_basic_escape_matches_black() {
+        assert_eq!(nearest_basic_escape(0, 0, 0), "\x1B[30m");
+    }
+
+    #[cfg(feature = "size-color")]
+    #[test]
+    fn gradient_color_is_green_below_the_floor() {
+        assert_eq!(gradient_color(1, 100, 1000), ANSI_16_RGB[2]);
+    }
+
+    #[cfg(feature = "size-color")]
+    #[test]
+    fn gradient_color_is_red_at_the_ceiling() {
+        assert_eq!(gradient_color(1000, 100, 1000), ANSI_16_RGB[1]);
+    }
+
+    #[cfg(feature = "size-color")]
+    #[test]
+    fn gradient_color_is_yellow_at_the_midpoint() {
+        assert_eq!(gradient_color(316, 100, 1000), ANSI_16_RGB[3]);
+    }
+
+    #[test]
+    fn select_named_groups_orders_disjoint_groups_left_to_right() {
+        let re = regex::Regex::new(r"(?P<date>\d+)-(?P<time>\d+)").unwrap();
+        let caps = re.captures("123-456").unwrap();
+        let date_color = Color::Ansi(AnsiColor::Basic(1));
+        let time_color = Color::Ansi(AnsiColor::Basic(2));
+        let name_colors: HashMap<&str, &Color> =
+            HashMap::from([("time", &time_color), ("date", &date_color)]);
+
+        let groups = select_named_groups(&caps, &name_colors).unwrap();
+
+        assert_eq!(groups.iter().map(|&(s, e, _)| (s, e)).collect::<Vec<_>>(), vec![(0, 3), (4, 7)]);
+    }
+
+    #[test]
+    fn select_named_groups_errors_on_nested_groups() {
+        // A timestamp-with-subfield pattern: `time` nests inside `timestamp`.
+        let re = regex::Regex::new(r"(?P<timestamp>\d{2}:(?P<time>\d{2}))").unwrap();
+        let caps = re.captures("12:34").unwrap();
+        let timestamp_color = Color::Ansi(AnsiColor::Basic(6));
+        let time_color = Color::Ansi(AnsiColor::Basic(1));
+        let name_colors: HashMap<&str, &Color> =
+            HashMap::from([("timestamp", &timestamp_color), ("time", &time_color)]);
+
+        assert!(select_named_groups(&caps, &name_colors).is_err());
+    }
+
+    #[test]
+    fn select_named_groups_breaks_ties_by_name_not_hash_order() {
+        // `a` and `b` both match the empty string at the same offset, so
+        // selection order must come from a deterministic tiebreak, not
+        // `name_colors`' (randomized) hash-map iteration order.
+        let re = regex::Regex::new(r"(?P<b>)(?P<a>)").unwrap();
+        let caps = re.captures("").unwrap();
+        let color_a = Color::Ansi(AnsiColor::Basic(2));
+        let color_b = Color::Ansi(AnsiColor::Basic(1));
+        let name_colors: HashMap<&str, &Color> = HashMap::from([("b", &color_b), ("a", &color_a)]);
+
+        let groups = select_named_groups(&caps, &name_colors).unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert!(matches!(*groups[0].2, Color::Ansi(AnsiColor::Basic(2))));
+    }
+}